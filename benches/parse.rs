@@ -0,0 +1,83 @@
+//! Benchmarks the parser's hot scanning loops (`read_to`/`read_while`/`skip_comment`/
+//! `read_raw_text_until_end_tag`) against multi-megabyte HTML, to track the speedup from the
+//! `memchr`-backed scanning in `parser.rs`.
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use tl::ParserOptions;
+
+/// Builds a synthetic HTML document of roughly `target_len` bytes by repeating a template
+/// fragment that exercises tags, attributes, text runs and comments alike.
+fn generate_html(target_len: usize) -> String {
+    let fragment = r#"<div class="row" id="row-ITER" data-note="nothing to see here">
+        <p>Some filler text to make read_to/read_while do real work, including &amp; entities.</p>
+        <!-- a comment that is long enough to matter for skip_comment -->
+        <a href="https://example.com/ITER">link ITER</a>
+    </div>
+"#;
+
+    let mut html = String::from("<!DOCTYPE html><html><body>");
+
+    let mut i = 0;
+    while html.len() < target_len {
+        html.push_str(&fragment.replace("ITER", &i.to_string()));
+        i += 1;
+    }
+
+    html.push_str("</body></html>");
+    html
+}
+
+/// Builds a synthetic document dominated by a handful of huge `<script>`/`<style>` bodies,
+/// roughly `target_len` bytes total, to exercise `read_raw_text_until_end_tag`.
+fn generate_script_heavy_html(target_len: usize) -> String {
+    let script_line = "console.log(\"row ITER\"); if (x < y) { doSomething(); }\n";
+    let style_line = ".row-ITER { color: red; border: 1px solid #000; }\n";
+
+    let mut script = String::new();
+    let mut style = String::new();
+
+    let mut i = 0;
+    while script.len() + style.len() < target_len {
+        script.push_str(&script_line.replace("ITER", &i.to_string()));
+        style.push_str(&style_line.replace("ITER", &i.to_string()));
+        i += 1;
+    }
+
+    format!("<!DOCTYPE html><html><head><style>{style}</style></head><body><script>{script}</script></body></html>")
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+
+    for size in [1, 4, 16] {
+        let html = generate_html(size * 1024 * 1024);
+
+        group.bench_with_input(BenchmarkId::from_parameter(format!("{size}MB")), &html, |b, html| {
+            b.iter(|| {
+                let dom = tl::parse(black_box(html), ParserOptions::new()).unwrap();
+                black_box(dom);
+            });
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_parse_script_heavy(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_script_heavy");
+
+    for size in [1, 4, 16] {
+        let html = generate_script_heavy_html(size * 1024 * 1024);
+
+        group.bench_with_input(BenchmarkId::from_parameter(format!("{size}MB")), &html, |b, html| {
+            b.iter(|| {
+                let dom = tl::parse(black_box(html), ParserOptions::new()).unwrap();
+                black_box(dom);
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse, bench_parse_script_heavy);
+criterion_main!(benches);
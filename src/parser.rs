@@ -1,7 +1,9 @@
-use crate::bytes::Bytes;
+use crate::bytes::{AsBytes, Bytes};
+use crate::entities;
 use crate::stream::Stream;
 use crate::util;
-use std::{borrow::Cow, collections::HashMap, rc::Rc};
+use memchr::{memchr, memchr2, memchr3, memmem};
+use std::{borrow::Cow, collections::HashMap};
 
 const OPENING_TAG: u8 = b'<';
 const END_OF_TAG: &[u8] = b"</";
@@ -13,6 +15,116 @@ const VOID_TAGS: &[&[u8]] = &[
     b"area", b"base", b"br", b"col", b"embed", b"hr", b"img", b"input", b"keygen", b"link",
     b"meta", b"param", b"source", b"track", b"wbr",
 ];
+/// Elements whose content is plain text that must never be interpreted as markup, even if it
+/// contains `<` (e.g. a `<script>` body full of comparison operators)
+const RAWTEXT_TAGS: &[&[u8]] = &[b"script", b"style", b"xmp", b"iframe", b"noembed", b"noframes"];
+/// Elements whose content is text that may still contain character references (unlike
+/// [`RAWTEXT_TAGS`]), but whose `<` must likewise never start a new tag
+const RCDATA_TAGS: &[&[u8]] = &[b"textarea", b"title"];
+/// Elements that (in [`ParserOptions::lenient`] mode) implicitly close when one of their trigger
+/// tags starts before they do, rather than being nested inside them - a simplified version of
+/// HTML5's "generate implied end tags", e.g. `<p>one<p>two</p>` becomes two sibling paragraphs
+/// instead of a `<p>` nested inside a `<p>`
+const IMPLIED_END_TAGS: &[(&[u8], &[&[u8]])] = &[
+    (
+        b"p",
+        &[
+            b"p", b"div", b"ul", b"ol", b"dl", b"table", b"section", b"article", b"aside",
+            b"header", b"footer", b"nav", b"h1", b"h2", b"h3", b"h4", b"h5", b"h6", b"blockquote",
+            b"pre", b"form", b"hr",
+        ],
+    ),
+    (b"li", &[b"li"]),
+    (b"option", &[b"option", b"optgroup"]),
+    (b"td", &[b"td", b"th", b"tr"]),
+    (b"th", &[b"td", b"th", b"tr"]),
+];
+
+/// A handle to a [`Node`] stored inside a [`Parser`]
+///
+/// Nodes are kept in a flat arena (see [`Parser::tags`]) rather than behind shared pointers,
+/// so a `NodeHandle` is simply an index that must be resolved through [`NodeHandle::get`] or
+/// [`NodeHandle::get_mut`] before it can be inspected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeHandle(usize);
+
+impl NodeHandle {
+    /// Creates a new `NodeHandle`, given the index it points to in `Parser::tags`
+    pub(crate) fn new(id: usize) -> Self {
+        Self(id)
+    }
+
+    /// Resolves this handle to the [`Node`] it points to
+    pub fn get<'p, 'a>(&self, parser: &'p Parser<'a>) -> Option<&'p Node<'a>> {
+        parser.tags.get(self.0)
+    }
+
+    /// Resolves this handle to a mutable reference to the [`Node`] it points to
+    pub fn get_mut<'p, 'a>(&self, parser: &'p mut Parser<'a>) -> Option<&'p mut Node<'a>> {
+        parser.tags.get_mut(self.0)
+    }
+
+    /// Returns the raw index wrapped by this handle
+    pub fn get_inner(&self) -> usize {
+        self.0
+    }
+}
+
+/// Options that influence how a [`Parser`] builds its DOM tree
+///
+/// By default, neither `id` nor `class` attributes are indexed, as not every caller needs
+/// [`VDom::get_element_by_id`](crate::VDom::get_element_by_id) or
+/// [`VDom::get_elements_by_class_name`](crate::VDom::get_elements_by_class_name) to be O(1) and
+/// building the side tables has a cost.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ParserOptions {
+    track_ids: bool,
+    track_classes: bool,
+    lenient: bool,
+}
+
+impl ParserOptions {
+    /// Creates a new, default `ParserOptions`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds a side table of `id` attributes so that element lookup by id is O(1)
+    pub fn track_ids(mut self) -> Self {
+        self.track_ids = true;
+        self
+    }
+
+    /// Builds a side table of `class` attributes so that element lookup by class is O(1)
+    pub fn track_classes(mut self) -> Self {
+        self.track_classes = true;
+        self
+    }
+
+    /// Recovers from malformed markup the way a browser would, instead of aborting the rest of
+    /// the subtree: a stray end tag with no matching open element anywhere is ignored, and an
+    /// end tag that matches an ancestor (e.g. `<b><i></b></i>`) implicitly closes everything in
+    /// between rather than failing to parse
+    ///
+    /// Off by default. Strict parsing is cheaper (no open-element stack to maintain, no scan on
+    /// a mismatched end tag) and is the right choice for callers who already control their input.
+    pub fn lenient(mut self) -> Self {
+        self.lenient = true;
+        self
+    }
+
+    pub(crate) fn is_tracking_ids(&self) -> bool {
+        self.track_ids
+    }
+
+    pub(crate) fn is_tracking_classes(&self) -> bool {
+        self.track_classes
+    }
+
+    pub(crate) fn is_lenient(&self) -> bool {
+        self.lenient
+    }
+}
 
 /// Stores all attributes of an HTML tag, as well as additional metadata such as `id` and `class`
 #[derive(Debug, Clone)]
@@ -34,15 +146,46 @@ impl<'a> Attributes<'a> {
             class: None,
         }
     }
+
+    /// Returns whether `class` is one of the space-separated class names of this element
+    pub fn is_class_member(&self, class: &str) -> bool {
+        let raw = match &self.class {
+            Some(raw) => raw,
+            None => return false,
+        };
+
+        raw.as_utf8_str()
+            .split_ascii_whitespace()
+            .any(|member| member == class)
+    }
+
+    /// Looks up an attribute by name (case-insensitively) and returns its value with any HTML
+    /// character references (`&amp;`, `&#169;`, ...) decoded
+    ///
+    /// Returns `None` if the attribute isn't present or has no value (e.g. `disabled` in
+    /// `<input disabled>`). Borrows from the original input when the value contains no
+    /// references, and only allocates once a substitution is actually made.
+    pub fn get_decoded(&self, key: &str) -> Option<Cow<'a, str>> {
+        let value = self
+            .raw
+            .iter()
+            .find(|(k, _)| k.as_utf8_str().eq_ignore_ascii_case(key))
+            .and_then(|(_, v)| v.as_ref())?;
+
+        Some(decode_cow(value.as_utf8_str()))
+    }
 }
 
 /// Represents a single HTML element
 #[derive(Debug, Clone)]
 pub struct HTMLTag<'a> {
-    _name: Option<Bytes<'a>>,
-    _attributes: Attributes<'a>,
-    _children: Vec<Rc<Node<'a>>>,
-    _raw: Bytes<'a>,
+    pub(crate) _name: Option<Bytes<'a>>,
+    pub(crate) _attributes: Attributes<'a>,
+    pub(crate) _children: Vec<NodeHandle>,
+    pub(crate) _raw: Bytes<'a>,
+    /// Set once this tag is mutated through `attributes_mut`/`children_mut`/`set_attribute`/etc.,
+    /// so that serialization knows to regenerate markup instead of reusing `_raw`
+    _modified: bool,
 }
 
 impl<'a> HTMLTag<'a> {
@@ -50,7 +193,7 @@ impl<'a> HTMLTag<'a> {
     pub(crate) fn new(
         name: Option<Bytes<'a>>,
         attr: Attributes<'a>,
-        children: Vec<Rc<Node<'a>>>,
+        children: Vec<NodeHandle>,
         raw: Bytes<'a>,
     ) -> Self {
         Self {
@@ -58,7 +201,164 @@ impl<'a> HTMLTag<'a> {
             _attributes: attr,
             _children: children,
             _raw: raw,
+            _modified: false,
+        }
+    }
+
+    /// Returns the name of this tag (e.g. `div`), if any
+    pub fn name(&self) -> Option<&Bytes<'a>> {
+        self._name.as_ref()
+    }
+
+    /// Returns the attributes of this tag
+    pub fn attributes(&self) -> &Attributes<'a> {
+        &self._attributes
+    }
+
+    /// Returns the handles of the direct children of this tag
+    pub fn children(&self) -> &[NodeHandle] {
+        &self._children
+    }
+
+    /// Returns a mutable reference to this tag's attributes
+    pub fn attributes_mut(&mut self) -> &mut Attributes<'a> {
+        self._modified = true;
+        &mut self._attributes
+    }
+
+    /// Returns a mutable reference to the handles of this tag's direct children
+    pub fn children_mut(&mut self) -> &mut Vec<NodeHandle> {
+        self._modified = true;
+        &mut self._children
+    }
+
+    /// Sets an attribute on this tag, overwriting any existing value
+    ///
+    /// Pass `None` to keep the attribute present without a value, as in the `disabled` of
+    /// `<input disabled>`. To remove the attribute entirely, use [`HTMLTag::remove_attribute`].
+    pub fn set_attribute<K, V>(&mut self, key: K, value: Option<V>)
+    where
+        K: Into<Bytes<'a>>,
+        V: Into<Bytes<'a>>,
+    {
+        let key = key.into();
+        let value = value.map(Into::into);
+
+        if key.raw().eq(ID_ATTR) {
+            self._attributes.id = value.clone();
+        } else if key.raw().eq(CLASS_ATTR) {
+            self._attributes.class = value.clone();
+        }
+
+        self._attributes.raw.insert(key, value);
+        self._modified = true;
+    }
+
+    /// Removes an attribute from this tag, returning its previous value if it was present
+    pub fn remove_attribute<S: ?Sized>(&mut self, key: &S) -> Option<Option<Bytes<'a>>>
+    where
+        S: AsBytes,
+    {
+        let bytes = key.as_bytes();
+
+        if bytes.raw().eq(ID_ATTR) {
+            self._attributes.id = None;
+        } else if bytes.raw().eq(CLASS_ATTR) {
+            self._attributes.class = None;
+        }
+
+        self._modified = true;
+        self._attributes.raw.remove(&bytes)
+    }
+
+    /// Appends an already-allocated node (see [`crate::VDom::create_raw_text`]) as the last child
+    pub fn append_child(&mut self, child: NodeHandle) {
+        self._children.push(child);
+        self._modified = true;
+    }
+
+    /// Removes the first child handle equal to `child`, returning whether one was found
+    pub fn remove_child(&mut self, child: NodeHandle) -> bool {
+        match self._children.iter().position(|&c| c == child) {
+            Some(position) => {
+                self._children.remove(position);
+                self._modified = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns whether this tag has been mutated since it was parsed
+    pub fn is_modified(&self) -> bool {
+        self._modified
+    }
+
+    /// Returns whether this tag, or any of its descendants, has been mutated since it was parsed
+    ///
+    /// `_modified` only reflects edits made directly to this tag; a cached `_raw` slice is stale
+    /// just as much when a *descendant* was edited, since it was captured before that happened.
+    fn is_subtree_modified(&self, parser: &Parser<'a>) -> bool {
+        self._modified
+            || self._children.iter().any(|&child| {
+                matches!(child.get(parser), Some(Node::Tag(t)) if t.is_subtree_modified(parser))
+            })
+    }
+
+    /// Serializes this tag, and recursively its children, back into HTML
+    ///
+    /// Tags whose whole subtree is untouched are emitted by simply writing out their original
+    /// [`HTMLTag::inner_html`]; tags reached through `attributes_mut`/`children_mut`/
+    /// `set_attribute`/etc., and every ancestor of such a tag, are regenerated from their name,
+    /// attributes and children instead, since `_raw` only borrows from the original input and
+    /// can't reflect structural edits.
+    pub fn to_html(&self, parser: &Parser<'a>) -> String {
+        let mut out = String::new();
+        self.write_html(parser, &mut out);
+        out
+    }
+
+    pub(crate) fn write_html(&self, parser: &Parser<'a>, out: &mut String) {
+        if !self.is_subtree_modified(parser) {
+            out.push_str(&self._raw.as_utf8_str());
+            return;
         }
+
+        let name = match &self._name {
+            Some(name) => name.as_utf8_str(),
+            None => return,
+        };
+
+        out.push('<');
+        out.push_str(&name);
+
+        for (key, value) in self._attributes.raw.iter() {
+            out.push(' ');
+            push_escaped_attribute_name(out, &key.as_utf8_str());
+
+            if let Some(value) = value {
+                out.push_str("=\"");
+                push_escaped_attribute_value(out, &value.as_utf8_str());
+                out.push('"');
+            }
+        }
+
+        if VOID_TAGS.contains(&name.as_bytes()) {
+            out.push_str(" />");
+            return;
+        }
+
+        out.push('>');
+
+        for &child in &self._children {
+            if let Some(node) = child.get(parser) {
+                node.write_html(parser, out);
+            }
+        }
+
+        out.push_str("</");
+        out.push_str(&name);
+        out.push('>');
     }
 
     /// Returns the contained markup
@@ -71,7 +371,7 @@ impl<'a> HTMLTag<'a> {
     /// Equivalent to [Element#innerText](https://developer.mozilla.org/en-US/docs/Web/API/Element/innerText) in browsers)
     /// This function may not allocate memory for a new string as it can just return the part of the tag that doesn't have markup
     /// For tags that *do* have more than one subnode, this will allocate memory
-    pub fn inner_text(&self) -> Cow<'a, str> {
+    pub fn inner_text(&self, parser: &Parser<'a>) -> Cow<'a, str> {
         let len = self._children.len();
 
         if len == 0 {
@@ -79,30 +379,35 @@ impl<'a> HTMLTag<'a> {
             return Cow::Borrowed("");
         }
 
-        let first = &self._children[0];
+        let first = self._children[0].get(parser);
 
         if len == 1 {
-            match &**first {
-                Node::Tag(t) => return t.inner_text(),
-                Node::Raw(e) => return e.as_utf8_str(),
-                Node::Comment(_) => return Cow::Borrowed(""),
-            }
+            return match first {
+                Some(Node::Tag(t)) => t.inner_text(parser),
+                Some(Node::Raw(e)) => e.as_utf8_str(),
+                Some(Node::Comment(_)) | None => Cow::Borrowed(""),
+            };
         }
 
         // If there are >1 nodes, we need to allocate a new string and push each inner_text in it
         // TODO: check if String::with_capacity() is worth it
-        let mut s = String::from(first.inner_text());
+        let mut s = String::from(first.map(|n| n.inner_text(parser)).unwrap_or_default());
 
-        for node in self._children.iter().skip(1) {
-            match &**node {
-                Node::Tag(t) => s.push_str(&t.inner_text()),
-                Node::Raw(e) => s.push_str(&e.as_utf8_str()),
-                Node::Comment(_) => { /* no op */ }
+        for handle in self._children.iter().skip(1) {
+            match handle.get(parser) {
+                Some(Node::Tag(t)) => s.push_str(&t.inner_text(parser)),
+                Some(Node::Raw(e)) => s.push_str(&e.as_utf8_str()),
+                Some(Node::Comment(_)) | None => { /* no op */ }
             }
         }
 
         Cow::Owned(s)
     }
+
+    /// Same as [`HTMLTag::inner_text`], but with HTML character references decoded
+    pub fn inner_text_decoded(&self, parser: &Parser<'a>) -> Cow<'a, str> {
+        decode_cow(self.inner_text(parser))
+    }
 }
 
 /// An HTML Node
@@ -118,17 +423,71 @@ pub enum Node<'a> {
 
 impl<'a> Node<'a> {
     /// Returns the inner text of this node
-    pub fn inner_text(&self) -> Cow<'a, str> {
+    pub fn inner_text(&self, parser: &Parser<'a>) -> Cow<'a, str> {
         match self {
             Node::Comment(_) => Cow::Borrowed(""),
             Node::Raw(r) => r.as_utf8_str(),
-            Node::Tag(t) => t.inner_text(),
+            Node::Tag(t) => t.inner_text(parser),
+        }
+    }
+
+    /// Same as [`Node::inner_text`], but with HTML character references decoded
+    pub fn inner_text_decoded(&self, parser: &Parser<'a>) -> Cow<'a, str> {
+        decode_cow(self.inner_text(parser))
+    }
+
+    /// Returns this node as an `HTMLTag`, if it is one
+    pub fn as_tag(&self) -> Option<&HTMLTag<'a>> {
+        match self {
+            Node::Tag(t) => Some(t),
+            _ => None,
+        }
+    }
+
+    /// Serializes this node, and recursively its children if it's a tag, back into HTML
+    pub fn to_html(&self, parser: &Parser<'a>) -> String {
+        let mut out = String::new();
+        self.write_html(parser, &mut out);
+        out
+    }
+
+    pub(crate) fn write_html(&self, parser: &Parser<'a>, out: &mut String) {
+        match self {
+            Node::Tag(t) => t.write_html(parser, out),
+            Node::Raw(r) => out.push_str(&r.as_utf8_str()),
+            // The trailing "-->" is already part of the raw slice captured by `skip_comment`
+            Node::Comment(c) => {
+                out.push_str("<!--");
+                out.push_str(&c.as_utf8_str());
+            }
+        }
+    }
+
+    /// Recursively searches the descendants of this node (depth-first) for one matching `f`
+    pub(crate) fn find_node<F>(&self, parser: &Parser<'a>, f: &mut F) -> Option<NodeHandle>
+    where
+        F: FnMut(&Node<'a>) -> bool,
+    {
+        let tag = self.as_tag()?;
+
+        for &child in tag.children() {
+            let node = child.get(parser)?;
+
+            if f(node) {
+                return Some(child);
+            }
+
+            if let Some(found) = node.find_node(parser, f) {
+                return Some(found);
+            }
         }
+
+        None
     }
 }
 
-/// A list of shared HTML nodes
-pub type Tree<'a> = Vec<Rc<Node<'a>>>;
+/// A list of node handles, as produced at the top level of a parsed document
+pub type Tree = Vec<NodeHandle>;
 
 /// HTML Version (<!DOCTYPE>)
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -146,20 +505,33 @@ pub enum HTMLVersion {
 #[derive(Debug)]
 pub struct Parser<'a> {
     pub stream: Stream<'a, u8>,
-    pub ast: Tree<'a>,
-    pub ids: HashMap<Bytes<'a>, Rc<Node<'a>>>,
-    pub classes: HashMap<Bytes<'a>, Vec<Rc<Node<'a>>>>,
+    pub ast: Tree,
+    /// Flat storage of every node produced while parsing; [`NodeHandle`]s are indices into this
+    pub tags: Vec<Node<'a>>,
+    pub ids: HashMap<Bytes<'a>, NodeHandle>,
+    pub classes: HashMap<Bytes<'a>, Vec<NodeHandle>>,
+    /// Maps a node to its parent, populated as nodes are parsed
+    pub parents: HashMap<NodeHandle, NodeHandle>,
     pub version: Option<HTMLVersion>,
+    pub options: ParserOptions,
+    /// The stack of tags currently being parsed, innermost last, used by
+    /// [`ParserOptions::lenient`] to figure out which ancestor a mismatched end tag actually
+    /// belongs to
+    open_elements: Vec<(&'a [u8], NodeHandle)>,
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(input: &str) -> Parser {
+    pub fn new(input: &'a str, options: ParserOptions) -> Parser<'a> {
         Parser {
             stream: Stream::new(input.as_bytes()),
             ast: Vec::new(),
+            tags: Vec::new(),
             ids: HashMap::new(),
             classes: HashMap::new(),
+            parents: HashMap::new(),
             version: None,
+            options,
+            open_elements: Vec::new(),
         }
     }
 
@@ -167,34 +539,50 @@ impl<'a> Parser<'a> {
         self.read_while(&[b' ', b'\n']);
     }
 
+    /// Returns everything from the current stream position to the end of the input, without
+    /// moving the stream
+    fn remaining(&self) -> &'a [u8] {
+        self.stream.slice_len(self.stream.idx, usize::MAX)
+    }
+
+    /// Scans forward for the next byte in `terminator`, the same way as a naive `position()`
+    /// loop would, but in bulk: one/two/three-byte terminator sets (by far the common case -
+    /// `read_to(&[b'<'])`, quoted attribute values, ...) go through a vectorized `memchr` search
+    /// that can skip many bytes per instruction instead of comparing one at a time
     fn read_to(&mut self, terminator: &[u8]) -> &'a [u8] {
         let start = self.stream.idx;
-
-        while !self.stream.is_eof() {
-            let ch = self.stream.current_unchecked();
-
-            let end = self.stream.idx;
-
-            if terminator.contains(ch) {
-                return self.stream.slice_unchecked(start, end);
-            }
-
-            self.stream.idx += 1;
-        }
-
-        self.stream.slice_unchecked(start, self.stream.idx)
+        let haystack = self.remaining();
+
+        let hit = match *terminator {
+            [a] => memchr(a, haystack),
+            [a, b] => memchr2(a, b, haystack),
+            [a, b, c] => memchr3(a, b, c, haystack),
+            _ => haystack.iter().position(|b| terminator.contains(b)),
+        };
+
+        let end = start + hit.unwrap_or(haystack.len());
+        self.stream.idx = end;
+        self.stream.slice_unchecked(start, end)
     }
 
+    /// Skips over a run of bytes that are all members of `terminator`
+    ///
+    /// Builds a 256-entry membership table once up front instead of re-scanning the (usually
+    /// tiny) `terminator` slice on every byte; unlike [`Parser::read_to`] it can't use `memchr`,
+    /// which has no primitive for this "keep going while in the set" direction.
     fn read_while(&mut self, terminator: &[u8]) {
-        while !self.stream.is_eof() {
-            let ch = self.stream.current_unchecked();
+        let mut is_terminator = [false; 256];
+        for &b in terminator {
+            is_terminator[b as usize] = true;
+        }
 
-            if !terminator.contains(ch) {
-                break;
-            }
+        let skip = self
+            .remaining()
+            .iter()
+            .take_while(|&&b| is_terminator[b as usize])
+            .count();
 
-            self.stream.idx += 1;
-        }
+        self.stream.idx += skip;
     }
 
     fn read_ident(&mut self) -> Option<&'a [u8]> {
@@ -214,26 +602,98 @@ impl<'a> Parser<'a> {
         None
     }
 
+    /// Reads the identifier starting at byte offset `at` without moving the stream, so the caller
+    /// can decide whether to actually consume it
+    ///
+    /// Unlike [`Parser::read_ident`], returns `None` when there is no identifier character at
+    /// `at`, and returns an identifier that runs up to the end of the input.
+    fn peek_ident_at(&self, at: usize) -> Option<&'a [u8]> {
+        let mut idx = at;
+
+        while self
+            .stream
+            .slice_len(idx, 1)
+            .first()
+            .map_or(false, |&c| util::is_ident(c))
+        {
+            idx += 1;
+        }
+
+        (idx > at).then(|| self.stream.slice(at, idx))
+    }
+
+    /// Peeks whether the upcoming token at the stream's current position is the start of an
+    /// opening tag (as opposed to an end tag, a markup declaration, or plain text), returning its
+    /// name without consuming anything
+    fn peek_start_tag_name(&self) -> Option<&'a [u8]> {
+        if self.stream.slice_len(self.stream.idx, 1).first() != Some(&OPENING_TAG) {
+            return None;
+        }
+
+        self.peek_ident_at(self.stream.idx + 1)
+    }
+
+    /// Consumes up to and including the comment's closing `-->`
+    ///
+    /// Jumps straight to the closing delimiter via a vectorized substring search instead of
+    /// probing for `--` one byte at a time and then peeking for the `>`.
     fn skip_comment(&mut self) -> Option<&'a [u8]> {
         let start = self.stream.idx;
+        let end = memmem::find(self.remaining(), b"-->")? + 3;
 
-        while !self.stream.is_eof() {
-            let idx = self.stream.idx;
+        self.stream.idx = start + end;
+        Some(self.stream.slice_unchecked(start, self.stream.idx))
+    }
 
-            if self.stream.slice_len(idx, COMMENT.len()).eq(COMMENT) {
-                self.stream.idx += COMMENT.len();
+    /// Consumes everything up to (but not including) the matching end tag for `name`, as
+    /// required while inside a RAWTEXT/RCDATA element (see [`RAWTEXT_TAGS`]/[`RCDATA_TAGS`])
+    ///
+    /// The end-tag name is matched case-insensitively, per the HTML5 tokenizer. Leaves the
+    /// stream positioned just past the end tag's `>`, or at EOF if no end tag is found.
+    ///
+    /// Only a `"</"` occurrence can ever start a valid end tag, so - like [`Parser::skip_comment`]
+    /// - this jumps straight from one `"</"` to the next via `memmem` instead of probing every
+    /// byte; `<script>`/`<style>` bodies are often the single largest text runs in a document, so
+    /// this loop matters just as much as `read_to`'s.
+    fn read_raw_text_until_end_tag(&mut self, name: &[u8]) -> &'a [u8] {
+        let start = self.stream.idx;
 
-                let is_end_of_comment = self.stream.expect_and_skip_cond(b'>');
+        loop {
+            let haystack = self.remaining();
 
-                if is_end_of_comment {
-                    return Some(self.stream.slice_unchecked(start, self.stream.idx));
+            let hit = match memmem::find(haystack, END_OF_TAG) {
+                Some(hit) => hit,
+                None => {
+                    self.stream.idx += haystack.len();
+                    break;
                 }
+            };
+
+            let idx = self.stream.idx + hit;
+            let name_start = idx + END_OF_TAG.len();
+            let candidate = self.stream.slice_len(name_start, name.len());
+
+            let next_boundary_ok = candidate.eq_ignore_ascii_case(name)
+                && self
+                    .stream
+                    .slice_len(name_start + name.len(), 1)
+                    .first()
+                    .map_or(true, |&c| c == b'>' || c == b'/' || util::is_strict_whitespace(c));
+
+            if next_boundary_ok {
+                let text = self.stream.slice_unchecked(start, idx);
+                self.stream.idx = name_start + name.len();
+                self.skip_whitespaces();
+                self.stream.expect_and_skip(b'>');
+                return text;
             }
 
-            self.stream.idx += 1;
+            // Not our end tag (wrong name, or just a stray "</" in the text) - keep scanning
+            // past it for the next candidate instead of restarting one byte later.
+            self.stream.idx = idx + END_OF_TAG.len();
         }
 
-        None
+        self.stream.slice_unchecked(start, self.stream.idx)
     }
 
     fn parse_attribute(&mut self) -> Option<(&'a [u8], Option<&'a [u8]>)> {
@@ -286,7 +746,65 @@ impl<'a> Parser<'a> {
         attributes
     }
 
-    fn parse_tag(&mut self, skip_current: bool) -> Option<Node<'a>> {
+    /// Pushes `node` into the arena, records its parent (if any) and returns its handle
+    fn push_node(&mut self, node: Node<'a>, parent: Option<NodeHandle>) -> NodeHandle {
+        let handle = NodeHandle::new(self.tags.len());
+        self.tags.push(node);
+
+        if let Some(parent) = parent {
+            self.parents.insert(handle, parent);
+        }
+
+        handle
+    }
+
+    /// Registers the `id`/`class` attributes of the tag stored at `handle`, if tracking is enabled
+    fn register_attributes(&mut self, handle: NodeHandle) {
+        let tag = match self.tags[handle.get_inner()].as_tag() {
+            Some(tag) => tag,
+            None => return,
+        };
+
+        if self.options.is_tracking_ids() {
+            if let Some(id) = tag.attributes().id.clone() {
+                self.ids.insert(id, handle);
+            }
+        }
+
+        if self.options.is_tracking_classes() {
+            if let Some(class) = tag.attributes().class.clone() {
+                self.process_class(&class, handle);
+            }
+        }
+    }
+
+    /// Returns every node handle reachable from [`Parser::ast`], in document order
+    ///
+    /// `tags` is a flat, append-only arena: a subtree abandoned by a mismatched/unclosed tag (see
+    /// [`ParserOptions::lenient`]) stays in it with no path back to `ast`, so callers that need
+    /// only the *live* document (e.g. [`crate::VDom::query_selector_all`]) must walk from `ast`
+    /// rather than iterate `tags` directly.
+    pub(crate) fn reachable_handles(&self) -> Vec<NodeHandle> {
+        let mut out = Vec::new();
+
+        for &handle in &self.ast {
+            self.collect_reachable(handle, &mut out);
+        }
+
+        out
+    }
+
+    fn collect_reachable(&self, handle: NodeHandle, out: &mut Vec<NodeHandle>) {
+        out.push(handle);
+
+        if let Some(Node::Tag(tag)) = handle.get(self) {
+            for &child in tag.children() {
+                self.collect_reachable(child, out);
+            }
+        }
+    }
+
+    fn parse_tag(&mut self, skip_current: bool, parent: Option<NodeHandle>) -> Option<NodeHandle> {
         let start = self.stream.idx;
 
         if skip_current {
@@ -306,7 +824,7 @@ impl<'a> Parser<'a> {
                 let comment = self.skip_comment()?;
 
                 // Comments are ignored, so we return no element
-                return Some(Node::Comment(comment.into()));
+                return Some(self.push_node(Node::Comment(comment.into()), parent));
             }
 
             let name = self.read_ident()?.to_ascii_uppercase();
@@ -338,6 +856,10 @@ impl<'a> Parser<'a> {
 
         let attributes = self.parse_attributes();
 
+        // Reserve this tag's slot up front so that its children can record it as their parent
+        // while they're being parsed, before this tag's own Node is fully built.
+        let handle = self.push_node(Node::Raw(Bytes::from(&b""[..])), parent);
+
         let mut children = Vec::new();
 
         let is_self_closing = self.stream.expect_and_skip_cond(b'/');
@@ -351,12 +873,10 @@ impl<'a> Parser<'a> {
 
             // If this is a self-closing tag (e.g. <img />), we want to return early instead of
             // reading children as the next nodes don't belong to this tag
-            return Some(Node::Tag(HTMLTag::new(
-                Some(name.into()),
-                attributes,
-                children,
-                raw.into(),
-            )));
+            self.tags[handle.get_inner()] =
+                Node::Tag(HTMLTag::new(Some(name.into()), attributes, children, raw.into()));
+            self.register_attributes(handle);
+            return Some(handle);
         }
 
         self.stream.expect_and_skip(b'>')?;
@@ -367,12 +887,39 @@ impl<'a> Parser<'a> {
             // Some HTML tags don't have contents (e.g. <br>),
             // so we need to return early
             // Without it, any following tags would be sub-nodes
-            return Some(Node::Tag(HTMLTag::new(
-                Some(name.into()),
-                attributes,
-                children,
-                raw.into(),
-            )));
+            self.tags[handle.get_inner()] =
+                Node::Tag(HTMLTag::new(Some(name.into()), attributes, children, raw.into()));
+            self.register_attributes(handle);
+            return Some(handle);
+        }
+
+        let lower_name = name.to_ascii_lowercase();
+
+        if RAWTEXT_TAGS.contains(&lower_name.as_slice()) || RCDATA_TAGS.contains(&lower_name.as_slice()) {
+            // `<script>`/`<style>`/`<textarea>`/... bodies are plain text: a `<` in there must
+            // never be mistaken for the start of a new tag, so we scan verbatim for the matching
+            // end tag instead of recursing into parse_single. Matched case-insensitively since
+            // `<SCRIPT>`/`<Script>`/... are just as much raw text per the HTML5 tokenizer, even
+            // though `RAWTEXT_TAGS`/`RCDATA_TAGS` themselves are all-lowercase.
+            let text = self.read_raw_text_until_end_tag(name);
+
+            if !text.is_empty() {
+                children.push(self.push_node(Node::Raw(text.into()), Some(handle)));
+            }
+
+            let raw = self.stream.slice_from(start);
+
+            self.tags[handle.get_inner()] =
+                Node::Tag(HTMLTag::new(Some(name.into()), attributes, children, raw.into()));
+            self.register_attributes(handle);
+            return Some(handle);
+        }
+
+        // Tracked so a mismatched end tag (see below) can tell whether it belongs to one of our
+        // ancestors, rather than having to abort the whole subtree just because it isn't ours.
+        let stack_depth = self.open_elements.len();
+        if self.options.is_lenient() {
+            self.open_elements.push((name, handle));
         }
 
         while !self.stream.is_eof() {
@@ -382,65 +929,87 @@ impl<'a> Parser<'a> {
 
             let slice = self.stream.slice(idx, idx + END_OF_TAG.len());
             if slice.eq(END_OF_TAG) {
-                self.stream.idx += END_OF_TAG.len();
-                let ident = self.read_ident()?;
-
-                if !ident.eq(name) {
-                    return None;
+                let ident = self.peek_ident_at(idx + END_OF_TAG.len());
+
+                match ident {
+                    Some(ident) if ident.eq(name) => {
+                        self.stream.idx = idx + END_OF_TAG.len() + ident.len();
+                        self.skip_whitespaces();
+                        self.stream.expect_and_skip(b'>');
+                        break;
+                    }
+                    Some(ident) if self.options.is_lenient() => {
+                        let closes_ancestor =
+                            self.open_elements.iter().any(|&(open, _)| open.eq(ident));
+
+                        if closes_ancestor {
+                            // This end tag belongs to an ancestor further up: stop here without
+                            // consuming it, so our caller's own end-tag check sees the same
+                            // bytes and implicitly closes us on its way back up the stack.
+                            break;
+                        }
+
+                        // No open element anywhere matches this end tag - it's stray/bogus
+                        // markup, so skip over it instead of aborting the whole subtree.
+                        self.stream.idx = idx + END_OF_TAG.len() + ident.len();
+                        self.skip_whitespaces();
+                        self.stream.expect_and_skip(b'>');
+                        continue;
+                    }
+                    _ => {
+                        // TODO: do we want to accept the tag if it has no closing tag?
+                        self.open_elements.truncate(stack_depth);
+                        return None;
+                    }
                 }
+            }
 
-                // TODO: do we want to accept the tag if it has no closing tag?
-                self.stream.expect_and_skip(b'>');
-                break;
+            if self.options.is_lenient() {
+                if let Some(upcoming) = self.peek_start_tag_name() {
+                    if implies_close(name, upcoming) {
+                        // `upcoming` isn't valid nested inside us (e.g. a `<p>` can't contain
+                        // another `<p>`) - stop here without consuming it, so our caller parses
+                        // it as our sibling instead of our child.
+                        break;
+                    }
+                }
             }
 
-            // TODO: "partial" JS parser is needed to deal with script tags
-            let node = self.parse_single()?;
+            let node = match self.parse_single(Some(handle)) {
+                Some(node) => node,
+                None => {
+                    self.open_elements.truncate(stack_depth);
+                    return None;
+                }
+            };
 
             children.push(node);
         }
 
+        self.open_elements.truncate(stack_depth);
+
         let raw = self.stream.slice_from(start);
 
-        Some(Node::Tag(HTMLTag::new(
-            Some(name.into()),
-            attributes,
-            children,
-            raw.into(),
-        )))
+        self.tags[handle.get_inner()] =
+            Node::Tag(HTMLTag::new(Some(name.into()), attributes, children, raw.into()));
+        self.register_attributes(handle);
+        Some(handle)
     }
 
-    fn parse_single(&mut self) -> Option<Rc<Node<'a>>> {
+    fn parse_single(&mut self, parent: Option<NodeHandle>) -> Option<NodeHandle> {
         self.skip_whitespaces();
 
         let ch = self.stream.current_cpy()?;
 
         if ch == OPENING_TAG {
-            if let Some(tag) = self.parse_tag(true) {
-                let tag_rc = Rc::new(tag);
-
-                if let Node::Tag(tag) = &*tag_rc {
-                    let (id, class) = (&tag._attributes.id, &tag._attributes.class);
-
-                    if let Some(id) = id {
-                        self.ids.insert(id.clone(), tag_rc.clone());
-                    }
-
-                    if let Some(class) = class {
-                        self.process_class(class, tag_rc.clone());
-                    }
-                }
-
-                Some(tag_rc)
-            } else {
-                None
-            }
+            self.parse_tag(true, parent)
         } else {
-            Some(Rc::new(Node::Raw(self.read_to(&[b'<']).into())))
+            let raw = self.read_to(&[b'<']);
+            Some(self.push_node(Node::Raw(raw.into()), parent))
         }
     }
 
-    fn process_class(&mut self, class: &Bytes<'a>, element: Rc<Node<'a>>) {
+    fn process_class(&mut self, class: &Bytes<'a>, element: NodeHandle) {
         let raw = class.raw();
 
         let mut stream = Stream::new(raw);
@@ -463,8 +1032,8 @@ impl<'a> Parser<'a> {
                 if slice.len() > 0 {
                     self.classes
                         .entry(slice.into())
-                        .or_insert_with(|| Vec::new())
-                        .push(element.clone());
+                        .or_insert_with(Vec::new)
+                        .push(element);
                 }
 
                 last = idx + 1;
@@ -474,12 +1043,225 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// In lenient mode, consumes a stray end tag (e.g. the second `</div>` in `<p>a</p></div>`)
+    /// sitting at the document root, where there's no open element at all for it to belong to
+    ///
+    /// Leading whitespace is skipped either way. Returns `None` (without moving the stream past
+    /// that whitespace) if what follows isn't an end tag, so the caller can fall back to its
+    /// normal handling.
+    fn try_skip_end_tag(&mut self) -> Option<&'a [u8]> {
+        self.skip_whitespaces();
+
+        let idx = self.stream.idx;
+
+        if !self.stream.slice(idx, idx + END_OF_TAG.len()).eq(END_OF_TAG) {
+            return None;
+        }
+
+        let ident = self.peek_ident_at(idx + END_OF_TAG.len())?;
+
+        self.stream.idx = idx + END_OF_TAG.len() + ident.len();
+        self.skip_whitespaces();
+        self.stream.expect_and_skip(b'>');
+        Some(ident)
+    }
+
     pub(crate) fn parse(mut self) -> Parser<'a> {
         while !self.stream.is_eof() {
-            if let Some(node) = self.parse_single() {
-                self.ast.push(node);
+            if self.options.is_lenient() && self.try_skip_end_tag().is_some() {
+                // A stray end tag at the document root has no open ancestor to match against -
+                // any end tag here is automatically bogus, so skip it the same way the lenient
+                // in-tag recovery above does and keep parsing.
+                continue;
+            }
+
+            if let Some(handle) = self.parse_single(None) {
+                self.ast.push(handle);
             }
         }
         self
     }
 }
+
+/// Decodes HTML character references in `text`, reusing the borrow if none are present
+fn decode_cow(text: Cow<'_, str>) -> Cow<'_, str> {
+    match entities::decode(&text) {
+        Cow::Borrowed(_) => text,
+        Cow::Owned(decoded) => Cow::Owned(decoded),
+    }
+}
+
+/// Returns whether `name` is one of the HTML elements that can never have children (e.g. `<br>`)
+pub(crate) fn is_void_tag(name: &[u8]) -> bool {
+    VOID_TAGS.contains(&name)
+}
+
+/// Returns whether an element named `open` must be implicitly closed (see [`IMPLIED_END_TAGS`])
+/// when a new start tag named `upcoming` appears as what would otherwise be parsed as its child
+fn implies_close(open: &[u8], upcoming: &[u8]) -> bool {
+    IMPLIED_END_TAGS
+        .iter()
+        .find(|&&(tag, _)| tag.eq_ignore_ascii_case(open))
+        .map_or(false, |&(_, triggers)| {
+            triggers.iter().any(|trigger| trigger.eq_ignore_ascii_case(upcoming))
+        })
+}
+
+/// Appends `value` to `out`, escaping `&` and `"` so it stays well-formed inside a `"`-quoted
+/// attribute value
+pub(crate) fn push_escaped_attribute_value(out: &mut String, value: &str) {
+    for ch in value.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            ch => out.push(ch),
+        }
+    }
+}
+
+/// Appends `name` to `out` as a bare attribute name, neutralizing anything that could let it
+/// break out of the attribute/tag it's attached to (quotes, `=`, `<`, `>`, `/`, whitespace) in
+/// addition to `&`
+///
+/// Unlike [`push_escaped_attribute_value`], which only has to stay well-formed inside its
+/// surrounding quotes, a key is written completely unquoted (`<div KEY="...">`), so every
+/// character that's structurally significant there has to be neutralized, not just the ones that
+/// matter inside a quoted string.
+pub(crate) fn push_escaped_attribute_name(out: &mut String, name: &str) {
+    for ch in name.chars() {
+        match ch {
+            '&' | '"' | '\'' | '=' | '<' | '>' | '/' => {
+                out.push_str("&#");
+                out.push_str(&(ch as u32).to_string());
+                out.push(';');
+            }
+            ch if ch.is_whitespace() => {
+                out.push_str("&#");
+                out.push_str(&(ch as u32).to_string());
+                out.push(';');
+            }
+            ch => out.push(ch),
+        }
+    }
+}
+
+/// Appends `text` to `out`, escaping `&`, `<` and `>` so it can't be mistaken for markup
+///
+/// Used both by [`crate::Sanitizer`] and by [`crate::VDom::create_raw_text`], since a raw-text
+/// node inserted through the mutation API is meant to render as literal text, not markup.
+pub(crate) fn push_escaped_text(out: &mut String, text: &str) {
+    for ch in text.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            ch => out.push(ch),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VDom;
+
+    fn dom(input: &str) -> VDom<'_> {
+        VDom::from(Parser::new(input, ParserOptions::new()).parse())
+    }
+
+    #[test]
+    fn outer_html_reflects_mutation_of_a_nested_descendant() {
+        let mut vdom = dom(r#"<div><p id="target">old</p></div>"#);
+        let handle = vdom.get_element_by_id("target").unwrap();
+
+        if let Some(Node::Tag(tag)) = handle.get_mut(vdom.parser_mut()) {
+            tag.set_attribute("data-edited", Some("yes"));
+        }
+
+        // The mutated <p> is nested inside an untouched <div>; the <div> must still notice the
+        // edit and regenerate instead of replaying its stale cached `_raw`.
+        let html = vdom.outer_html();
+        assert!(html.contains("data-edited"), "mutation was lost: {html}");
+    }
+
+    #[test]
+    fn write_html_escapes_attribute_keys() {
+        let mut vdom = dom(r#"<div id="target"></div>"#);
+        let handle = vdom.get_element_by_id("target").unwrap();
+
+        if let Some(Node::Tag(tag)) = handle.get_mut(vdom.parser_mut()) {
+            tag.set_attribute("x\" onclick=\"evil", Some("1"));
+        }
+
+        let html = vdom.outer_html();
+        assert!(
+            !html.contains("onclick=\"evil"),
+            "attribute key injection was not escaped: {html}"
+        );
+    }
+
+    #[test]
+    fn rawtext_tags_are_matched_case_insensitively() {
+        // If `<SCRIPT>` isn't recognized as a raw-text tag, the `<` in `1<2` gets parsed as the
+        // start of a bogus element, corrupting everything that follows.
+        let vdom = dom("<SCRIPT>if (1<2) {}</SCRIPT><p>after</p>");
+        let parser = vdom.parser();
+
+        assert_eq!(vdom.children().len(), 2);
+
+        let p = vdom.children()[1].get(parser).and_then(Node::as_tag).unwrap();
+        assert_eq!(p.name().unwrap().as_utf8_str(), "p");
+        assert_eq!(p.inner_text(parser), "after");
+    }
+
+    #[test]
+    fn lenient_mode_recovers_from_a_stray_end_tag_at_the_document_root() {
+        let input = "<p>a</p></div>more";
+        let vdom = VDom::from(Parser::new(input, ParserOptions::new().lenient()).parse());
+        let parser = vdom.parser();
+
+        // The stray `</div>` should be skipped rather than derailing the rest of the document.
+        assert_eq!(vdom.children().len(), 2);
+
+        let p = vdom.children()[0].get(parser).and_then(Node::as_tag).unwrap();
+        assert_eq!(p.name().unwrap().as_utf8_str(), "p");
+
+        let tail = vdom.children()[1].get(parser).unwrap();
+        assert_eq!(tail.inner_text(parser), "more");
+    }
+
+    #[test]
+    fn raw_text_scan_skips_stray_end_tags_of_other_elements() {
+        // A stray "</div>" inside the script body isn't a valid end tag for it (wrong name), so
+        // the scan has to keep looking past it rather than stopping there.
+        let vdom = dom("<script>if (x) { console.log(\"</div>\"); }</script><p>after</p>");
+        let parser = vdom.parser();
+
+        assert_eq!(vdom.children().len(), 2);
+
+        let script = vdom.children()[0].get(parser).and_then(Node::as_tag).unwrap();
+        assert_eq!(script.name().unwrap().as_utf8_str(), "script");
+        assert!(script.inner_text(parser).contains("</div>"));
+
+        let p = vdom.children()[1].get(parser).and_then(Node::as_tag).unwrap();
+        assert_eq!(p.name().unwrap().as_utf8_str(), "p");
+    }
+
+    #[test]
+    fn lenient_mode_implicitly_closes_a_p_before_a_sibling_p() {
+        let vdom = VDom::from(Parser::new("<p>one<p>two</p>", ParserOptions::new().lenient()).parse());
+        let parser = vdom.parser();
+
+        // Without implied end tags this would parse as one <p> nested inside the other.
+        assert_eq!(vdom.children().len(), 2);
+
+        let first = vdom.children()[0].get(parser).and_then(Node::as_tag).unwrap();
+        assert_eq!(first.name().unwrap().as_utf8_str(), "p");
+        assert_eq!(first.inner_text(parser), "one");
+        assert!(first.children().iter().all(|&child| !matches!(child.get(parser), Some(Node::Tag(_)))));
+
+        let second = vdom.children()[1].get(parser).and_then(Node::as_tag).unwrap();
+        assert_eq!(second.name().unwrap().as_utf8_str(), "p");
+        assert_eq!(second.inner_text(parser), "two");
+    }
+}
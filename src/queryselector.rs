@@ -0,0 +1,379 @@
+//! A small CSS selector engine backing [`VDom::query_selector`](crate::VDom::query_selector) and
+//! [`VDom::query_selector_all`](crate::VDom::query_selector_all).
+//!
+//! Only a practical subset of CSS is supported: tag names, `*`, `#id`, `.class`, the five
+//! `[attr...]` attribute matchers and the descendant/child/sibling combinators. There is no
+//! support for pseudo-classes, pseudo-elements or comma-separated selector lists.
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::parser::{HTMLTag, Node, NodeHandle, Parser};
+
+/// How two compound selectors in a chain relate to one another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Combinator {
+    /// ` ` - rightmost matches any descendant of the left
+    Descendant,
+    /// `>` - rightmost matches a direct child of the left
+    Child,
+    /// `+` - rightmost matches the element immediately following the left
+    NextSibling,
+    /// `~` - rightmost matches any sibling following the left
+    SubsequentSibling,
+}
+
+/// How an attribute selector (`[attr...]`) compares its value
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum AttrMatch {
+    /// `[attr]`
+    Exists,
+    /// `[attr=val]`
+    Exact(String),
+    /// `[attr^=val]`
+    Prefix(String),
+    /// `[attr$=val]`
+    Suffix(String),
+    /// `[attr*=val]`
+    Substring(String),
+}
+
+impl AttrMatch {
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            AttrMatch::Exists => true,
+            AttrMatch::Exact(v) => value == v,
+            AttrMatch::Prefix(v) => value.starts_with(v.as_str()),
+            AttrMatch::Suffix(v) => value.ends_with(v.as_str()),
+            AttrMatch::Substring(v) => value.contains(v.as_str()),
+        }
+    }
+}
+
+/// A single compound selector, e.g. `div.post#main[data-x]`
+#[derive(Debug, Clone, Default)]
+struct SimpleSelector {
+    tag: Option<String>,
+    id: Option<String>,
+    classes: Vec<String>,
+    attributes: Vec<(String, AttrMatch)>,
+}
+
+impl SimpleSelector {
+    fn matches(&self, tag: &HTMLTag) -> bool {
+        if let Some(name) = &self.tag {
+            let matches_name = tag
+                .name()
+                .map_or(false, |n| n.as_utf8_str().eq_ignore_ascii_case(name));
+
+            if !matches_name {
+                return false;
+            }
+        }
+
+        if let Some(id) = &self.id {
+            let matches_id = tag
+                .attributes()
+                .id
+                .as_ref()
+                .map_or(false, |v| v.as_utf8_str() == id.as_str());
+
+            if !matches_id {
+                return false;
+            }
+        }
+
+        if !self.classes.iter().all(|c| tag.attributes().is_class_member(c)) {
+            return false;
+        }
+
+        self.attributes.iter().all(|(name, matcher)| {
+            let found = tag
+                .attributes()
+                .raw
+                .iter()
+                .find(|(k, _)| k.as_utf8_str().eq_ignore_ascii_case(name));
+
+            match found {
+                Some((_, Some(value))) => matcher.matches(&value.as_utf8_str()),
+                Some((_, None)) => *matcher == AttrMatch::Exists,
+                None => false,
+            }
+        })
+    }
+}
+
+/// A parsed CSS selector: a chain of compound selectors joined by combinators
+///
+/// Compounds are stored left-to-right as written; matching proceeds from the rightmost
+/// (most specific) compound and walks outwards towards its ancestors/siblings.
+#[derive(Debug, Clone)]
+pub(crate) struct Selector {
+    compounds: Vec<SimpleSelector>,
+    /// `combinators[i]` joins `compounds[i]` to `compounds[i + 1]`
+    combinators: Vec<Combinator>,
+}
+
+impl Selector {
+    /// Returns whether the node at `handle` matches this selector
+    pub(crate) fn matches(&self, parser: &Parser, handle: NodeHandle) -> bool {
+        let last = match self.compounds.last() {
+            Some(last) => last,
+            None => return false,
+        };
+
+        let tag = match handle.get(parser).and_then(Node::as_tag) {
+            Some(tag) => tag,
+            None => return false,
+        };
+
+        last.matches(tag) && self.matches_ancestors(parser, handle, self.compounds.len() - 1)
+    }
+
+    /// Verifies that the compounds to the left of `compound_idx` are satisfied by the
+    /// ancestors/siblings of the node at `handle`
+    fn matches_ancestors(&self, parser: &Parser, handle: NodeHandle, compound_idx: usize) -> bool {
+        if compound_idx == 0 {
+            return true;
+        }
+
+        let combinator = self.combinators[compound_idx - 1];
+        let compound = &self.compounds[compound_idx - 1];
+
+        match combinator {
+            Combinator::Descendant => {
+                let mut current = handle;
+
+                while let Some(&parent) = parser.parents.get(&current) {
+                    if let Some(tag) = parent.get(parser).and_then(Node::as_tag) {
+                        if compound.matches(tag) && self.matches_ancestors(parser, parent, compound_idx - 1) {
+                            return true;
+                        }
+                    }
+
+                    current = parent;
+                }
+
+                false
+            }
+            Combinator::Child => {
+                let parent = match parser.parents.get(&handle) {
+                    Some(&parent) => parent,
+                    None => return false,
+                };
+
+                let matches_parent = parent.get(parser).and_then(Node::as_tag).map_or(false, |tag| compound.matches(tag));
+
+                matches_parent && self.matches_ancestors(parser, parent, compound_idx - 1)
+            }
+            Combinator::NextSibling | Combinator::SubsequentSibling => {
+                let parent = match parser.parents.get(&handle) {
+                    Some(&parent) => parent,
+                    None => return false,
+                };
+
+                let siblings = match parent.get(parser).and_then(Node::as_tag) {
+                    Some(tag) => tag.children(),
+                    None => return false,
+                };
+
+                let pos = match siblings.iter().position(|&sibling| sibling == handle) {
+                    Some(pos) => pos,
+                    None => return false,
+                };
+
+                // Sibling combinators only care about preceding *elements* - `Node::Raw`/
+                // `Node::Comment` children (plain text, whitespace, comments) in between don't
+                // count, the same way CSS ignores them.
+                let mut preceding_elements = siblings[..pos]
+                    .iter()
+                    .rev()
+                    .filter(|&&sibling| matches!(sibling.get(parser), Some(Node::Tag(_))));
+
+                if combinator == Combinator::NextSibling {
+                    preceding_elements.next().map_or(false, |&sibling| {
+                        sibling.get(parser).and_then(Node::as_tag).map_or(false, |tag| compound.matches(tag))
+                            && self.matches_ancestors(parser, sibling, compound_idx - 1)
+                    })
+                } else {
+                    preceding_elements.any(|&sibling| {
+                        sibling.get(parser).and_then(Node::as_tag).map_or(false, |tag| compound.matches(tag))
+                            && self.matches_ancestors(parser, sibling, compound_idx - 1)
+                    })
+                }
+            }
+        }
+    }
+}
+
+/// Parses a CSS selector string into a [`Selector`], returning `None` if it is malformed
+pub(crate) fn parse_selector(input: &str) -> Option<Selector> {
+    let mut chars = input.trim().chars().peekable();
+    let mut compounds = Vec::new();
+    let mut combinators = Vec::new();
+
+    loop {
+        skip_whitespace(&mut chars);
+        compounds.push(parse_compound(&mut chars)?);
+        let had_whitespace = skip_whitespace(&mut chars);
+
+        match chars.peek().copied() {
+            None => break,
+            Some('>') => {
+                chars.next();
+                skip_whitespace(&mut chars);
+                combinators.push(Combinator::Child);
+            }
+            Some('+') => {
+                chars.next();
+                skip_whitespace(&mut chars);
+                combinators.push(Combinator::NextSibling);
+            }
+            Some('~') => {
+                chars.next();
+                skip_whitespace(&mut chars);
+                combinators.push(Combinator::SubsequentSibling);
+            }
+            Some(_) if had_whitespace => combinators.push(Combinator::Descendant),
+            Some(_) => return None,
+        }
+    }
+
+    Some(Selector { compounds, combinators })
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) -> bool {
+    let mut skipped = false;
+
+    while chars.peek().map_or(false, |c| c.is_whitespace()) {
+        chars.next();
+        skipped = true;
+    }
+
+    skipped
+}
+
+fn parse_compound(chars: &mut Peekable<Chars>) -> Option<SimpleSelector> {
+    let mut selector = SimpleSelector::default();
+    let mut matched_anything = false;
+
+    loop {
+        match chars.peek().copied() {
+            Some('*') => {
+                chars.next();
+                matched_anything = true;
+            }
+            Some('#') => {
+                chars.next();
+                selector.id = Some(read_ident(chars)?);
+                matched_anything = true;
+            }
+            Some('.') => {
+                chars.next();
+                selector.classes.push(read_ident(chars)?);
+                matched_anything = true;
+            }
+            Some('[') => {
+                chars.next();
+                selector.attributes.push(parse_attribute_selector(chars)?);
+                matched_anything = true;
+            }
+            Some(c) if is_ident_start(c) => {
+                selector.tag = Some(read_ident(chars)?);
+                matched_anything = true;
+            }
+            _ => break,
+        }
+    }
+
+    matched_anything.then(|| selector)
+}
+
+fn parse_attribute_selector(chars: &mut Peekable<Chars>) -> Option<(String, AttrMatch)> {
+    let name = read_ident(chars)?;
+
+    match chars.peek().copied() {
+        Some(']') => {
+            chars.next();
+            Some((name, AttrMatch::Exists))
+        }
+        Some('=') => {
+            chars.next();
+            let value = read_attr_value(chars)?;
+            (chars.next() == Some(']')).then(|| (name, AttrMatch::Exact(value)))
+        }
+        Some(op @ ('^' | '$' | '*')) => {
+            chars.next();
+            if chars.next() != Some('=') {
+                return None;
+            }
+
+            let value = read_attr_value(chars)?;
+
+            if chars.next() != Some(']') {
+                return None;
+            }
+
+            let matcher = match op {
+                '^' => AttrMatch::Prefix(value),
+                '$' => AttrMatch::Suffix(value),
+                _ => AttrMatch::Substring(value),
+            };
+
+            Some((name, matcher))
+        }
+        _ => None,
+    }
+}
+
+fn read_attr_value(chars: &mut Peekable<Chars>) -> Option<String> {
+    match chars.peek().copied() {
+        Some(quote @ ('"' | '\'')) => {
+            chars.next();
+            let mut value = String::new();
+
+            loop {
+                match chars.next()? {
+                    c if c == quote => break,
+                    c => value.push(c),
+                }
+            }
+
+            Some(value)
+        }
+        _ => read_ident(chars),
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '-' || c == '_'
+}
+
+fn is_ident_continue(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_'
+}
+
+fn read_ident(chars: &mut Peekable<Chars>) -> Option<String> {
+    let mut ident = String::new();
+
+    while chars.peek().map_or(false, |&c| is_ident_continue(c)) {
+        ident.push(chars.next().unwrap());
+    }
+
+    (!ident.is_empty()).then(|| ident)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Parser, ParserOptions, VDom};
+
+    #[test]
+    fn next_sibling_combinator_skips_intervening_text_nodes() {
+        let vdom = VDom::from(Parser::new("<div><a>1</a>x<b>2</b></div>", ParserOptions::new()).parse());
+
+        let matched = vdom.query_selector_all("a + b").unwrap().next();
+        assert!(matched.is_some(), "`a + b` should skip the text node between them");
+
+        let tag = matched.unwrap().get(vdom.parser()).and_then(crate::Node::as_tag).unwrap();
+        assert_eq!(tag.name().unwrap().as_utf8_str(), "b");
+    }
+}
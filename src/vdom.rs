@@ -1,4 +1,5 @@
 use crate::parser::NodeHandle;
+use crate::queryselector;
 use crate::ParserOptions;
 use crate::{bytes::AsBytes, parser::HTMLVersion};
 use crate::{Node, Parser};
@@ -28,6 +29,53 @@ impl<'a> VDom<'a> {
         &self.parser
     }
 
+    /// Returns a mutable reference to the underlying parser
+    #[inline]
+    pub fn parser_mut(&mut self) -> &mut Parser<'a> {
+        &mut self.parser
+    }
+
+    /// Allocates a new raw-text node in the document's internal storage and returns a handle to it
+    ///
+    /// The returned handle isn't part of the tree until it's attached as a child of some tag via
+    /// [`HTMLTag::append_child`](crate::HTMLTag::append_child). `text` is treated as plain text,
+    /// not markup: `&`, `<` and `>` are escaped so it can't be used to smuggle a new element or
+    /// attribute into the document when the tree is serialized back out.
+    pub fn create_raw_text<B>(&mut self, text: B) -> NodeHandle
+    where
+        B: Into<crate::bytes::Bytes<'a>>,
+    {
+        let text = text.into();
+        let mut escaped = String::new();
+        crate::parser::push_escaped_text(&mut escaped, &text.as_utf8_str());
+
+        let parser = self.parser_mut();
+        let handle = NodeHandle::new(parser.tags.len());
+        parser.tags.push(Node::Raw(escaped.into()));
+        handle
+    }
+
+    /// Serializes the entire document back into HTML
+    ///
+    /// Nodes whose whole subtree is untouched are emitted verbatim via their original markup;
+    /// nodes reached through
+    /// [`HTMLTag::attributes_mut`](crate::HTMLTag::attributes_mut),
+    /// [`HTMLTag::children_mut`](crate::HTMLTag::children_mut),
+    /// [`HTMLTag::set_attribute`](crate::HTMLTag::set_attribute) or similar, and their
+    /// ancestors, are regenerated from their name, attributes and children instead.
+    pub fn outer_html(&self) -> String {
+        let parser = self.parser();
+        let mut out = String::new();
+
+        for &handle in self.children() {
+            if let Some(node) = handle.get(parser) {
+                node.write_html(parser, &mut out);
+            }
+        }
+
+        out
+    }
+
     /// Finds an element by its `id` attribute.
     pub fn get_element_by_id<'b, S: ?Sized>(&'b self, id: &'b S) -> Option<NodeHandle>
     where
@@ -99,6 +147,36 @@ impl<'a> VDom<'a> {
         &self.parser.ast
     }
 
+    /// Finds all elements matching the given CSS selector
+    ///
+    /// Supports tag names, `*`, `#id`, `.class`, the attribute matchers `[attr]`, `[attr=val]`,
+    /// `[attr^=val]`, `[attr$=val]` and `[attr*=val]`, and the descendant (` `), child (`>`),
+    /// next-sibling (`+`) and subsequent-sibling (`~`) combinators.
+    ///
+    /// Returns `None` if `selector` could not be parsed. Comma-separated selector lists and
+    /// pseudo-classes/pseudo-elements are not supported.
+    pub fn query_selector_all<'b>(
+        &'b self,
+        selector: &'b str,
+    ) -> Option<Box<dyn Iterator<Item = NodeHandle> + 'b>> {
+        let selector = queryselector::parse_selector(selector)?;
+        let parser = self.parser();
+
+        Some(Box::new(
+            parser
+                .reachable_handles()
+                .into_iter()
+                .filter(move |handle| selector.matches(parser, *handle)),
+        ))
+    }
+
+    /// Finds the first element matching the given CSS selector
+    ///
+    /// See [`VDom::query_selector_all`] for the supported selector syntax.
+    pub fn query_selector(&self, selector: &str) -> Option<NodeHandle> {
+        self.query_selector_all(selector)?.next()
+    }
+
     /// Returns the HTML version.
     /// This is determined by the `<!DOCTYPE>` tag
     pub fn version(&self) -> Option<HTMLVersion> {
@@ -182,3 +260,24 @@ impl<'a> Drop for VDomGuard<'a> {
         drop(unsafe { Box::from_raw(self.ptr) });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::VDom;
+    use crate::{Parser, ParserOptions};
+
+    #[test]
+    fn query_selector_all_ignores_orphaned_fragments() {
+        // Non-lenient: the stray `</b>` has no matching ancestor, so the `<div>` aborts and is
+        // never added to `ast` - but the already-fully-parsed `<span>` subtree it was building up
+        // stays behind in the arena with no path back to `ast`.
+        let input = r#"<div><span id="orphan">inner</span></b></div>"#;
+        let vdom = VDom::from(Parser::new(input, ParserOptions::new()).parse());
+
+        assert!(vdom.children().is_empty());
+        assert!(
+            vdom.query_selector_all("span").unwrap().next().is_none(),
+            "matched a span that isn't reachable from the document root"
+        );
+    }
+}